@@ -2,16 +2,57 @@
 pub fn mv<T>(x: T) -> T { x }
 
 
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
 use std::marker::PhantomData;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::task::{Context, Poll};
 
 #[derive(Debug)]
 struct NonCopyBool(bool);
 
+/// Returned by the fallible borrow methods when the borrow-state flag
+/// would otherwise be violated, or when the value has already been moved
+/// out via `take`. Carries enough detail to tell those causes apart.
+#[derive(Debug)]
+pub enum AccessError {
+    /// A mutable (`RefMut`/`SyncRefMut`/`OwnedRefMut`) guard is currently
+    /// outstanding.
+    AlreadyMutablyBorrowed,
+    /// One or more shared (`Ref`/`SyncRef`/`OwnedRef`) guards are
+    /// currently outstanding.
+    AlreadyBorrowed,
+    /// The value has already been moved out by a prior `take`.
+    Taken,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::AlreadyMutablyBorrowed => f.write_str("already mutably borrowed"),
+            AccessError::AlreadyBorrowed => f.write_str("already borrowed"),
+            AccessError::Taken => f.write_str("value was already taken"),
+        }
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+/// `0`: unborrowed. `n > 0`: `n` outstanding shared borrows. `n < 0`: one
+/// outstanding mutable borrow.
+const UNUSED: isize = 0;
+
 struct UnsafeShared<T> {
     inner: UnsafeCell<T>,
+    borrow: Cell<isize>,
 }
 
+/// A raw, unchecked borrow of the value inside an `UnsafeShared`. Unlike
+/// `Ref`/`RefMut`, obtaining one does not touch the borrow-state flag, so
+/// the caller is responsible for upholding aliasing rules themselves.
 struct UnsafeRef<'a, T: 'a> {
     ptr: *mut T,
     marker: PhantomData<&'a mut T>
@@ -36,9 +77,51 @@ impl <'a, T>std::ops::DerefMut for UnsafeRef<'a, T> {
     }
 }
 
-impl <'a, T: 'a> Drop for UnsafeRef<'a, T> {
+/// A checked shared-borrow guard, returned by [`UnsafeShared::borrow`] and
+/// [`UnsafeShared::try_borrow`]. Releases its borrow on drop.
+struct Ref<'a, T: 'a> {
+    ptr: *const T,
+    borrow: &'a Cell<isize>,
+}
+
+impl<'a, T> std::ops::Deref for Ref<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T> Drop for Ref<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.set(self.borrow.get() - 1);
+    }
+}
+
+/// A checked mutable-borrow guard, returned by [`UnsafeShared::borrow_mut`]
+/// and [`UnsafeShared::try_borrow_mut`]. Releases its borrow on drop.
+struct RefMut<'a, T: 'a> {
+    ptr: *mut T,
+    borrow: &'a Cell<isize>,
+}
+
+impl<'a, T> std::ops::Deref for RefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for RefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'a, T> Drop for RefMut<'a, T> {
     fn drop(&mut self) {
-        println!("{:p}", self);
+        self.borrow.set(UNUSED);
     }
 }
 
@@ -46,30 +129,808 @@ impl<T> UnsafeShared<T> {
     fn new(value: T) -> Self {
         Self {
             inner: UnsafeCell::new(value),
+            borrow: Cell::new(UNUSED),
         }
     }
 
-    /// The caller must ensure there are no references to the inner value when this is called.
-    fn borrow_mut(&self) -> &mut T {
-        unsafe { &mut *self.inner.get() }
+    /// Panics if the value is currently mutably borrowed.
+    fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().unwrap_or_else(|e| panic!("{}", e))
     }
 
-    /// The caller must ensure there is no mutable reference to the inner value when this is called.
-    fn borrow(&self) -> &T {
-        unsafe { &*self.inner.get() }
+    /// Panics if the value is currently borrowed, mutably or otherwise.
+    fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut().unwrap_or_else(|e| panic!("{}", e))
     }
 
-    fn as_ref<'a>(&self) -> UnsafeRef<'a, T> {
+    fn try_borrow(&self) -> Result<Ref<'_, T>, AccessError> {
+        let b = self.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        self.borrow.set(b + 1);
+        Ok(Ref {
+            ptr: self.inner.get(),
+            borrow: &self.borrow,
+        })
+    }
+
+    fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, AccessError> {
+        let b = self.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        if b > UNUSED {
+            return Err(AccessError::AlreadyBorrowed);
+        }
+        self.borrow.set(-1);
+        Ok(RefMut {
+            ptr: self.inner.get(),
+            borrow: &self.borrow,
+        })
+    }
+
+    /// Bypasses all borrow-state bookkeeping. The caller must ensure the
+    /// aliasing rules aren't violated for the lifetime of the returned ref.
+    unsafe fn as_unsafe_ref<'a>(&self) -> UnsafeRef<'a, T> {
         UnsafeRef::new(self.inner.get())
     }
+
+    /// Panics if a `Ref`/`RefMut` guard is currently outstanding.
+    fn check_unborrowed(&self) {
+        let b = self.borrow.get();
+        if b < UNUSED {
+            panic!("{}", AccessError::AlreadyMutablyBorrowed);
+        }
+        if b > UNUSED {
+            panic!("{}", AccessError::AlreadyBorrowed);
+        }
+    }
+
+    /// Returns a copy of the contained value. Mirrors `Cell::get`.
+    fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        self.check_unborrowed();
+        unsafe { *self.inner.get() }
+    }
+
+    /// Sets the contained value, dropping the old one. Mirrors `Cell::set`.
+    fn set(&self, val: T) {
+        drop(self.replace(val));
+    }
+
+    /// Replaces the contained value and returns the old one. Mirrors
+    /// `Cell::replace`.
+    fn replace(&self, val: T) -> T {
+        self.check_unborrowed();
+        unsafe { std::mem::replace(&mut *self.inner.get(), val) }
+    }
+
+    /// Swaps the values of two `UnsafeShared`s. Mirrors `Cell::swap`.
+    fn swap(&self, other: &UnsafeShared<T>) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        self.check_unborrowed();
+        other.check_unborrowed();
+        unsafe { std::ptr::swap(self.inner.get(), other.inner.get()) }
+    }
+
+    /// Replaces the contained value with the result of `f`, fed the current
+    /// value. Mirrors the unstable `Cell::update`.
+    fn update(&self, f: impl FnOnce(T) -> T)
+    where
+        T: Copy,
+    {
+        let old = self.get();
+        self.set(f(old));
+    }
+
+    /// Takes the value, leaving `Default::default()` in its place. Mirrors
+    /// `Cell::take`.
+    fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    /// `&mut self` already proves there are no outstanding `Ref`/`RefMut`
+    /// guards, so this needs no runtime check and no `unsafe` at the call
+    /// site.
+    fn get_mut(&mut self) -> &mut T {
+        debug_assert_eq!(self.borrow.get(), UNUSED, "borrow flag should be clear given exclusive access");
+        self.borrow.set(UNUSED);
+        self.inner.get_mut()
+    }
+
+    fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T> From<T> for UnsafeShared<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+struct SharedInner<T> {
+    value: UnsafeCell<Option<T>>,
+    borrow: Cell<isize>,
+    strong: Cell<usize>,
+}
+
+/// A heap-allocated, reference-counted sibling of `UnsafeShared` — a
+/// single-threaded `Rc<RefCell<T>>` replacement. Every clone shares the
+/// same allocation, and once every outstanding borrow is released the
+/// value can be reclaimed with `take`.
+struct Shared<T> {
+    ptr: NonNull<SharedInner<T>>,
+    marker: PhantomData<SharedInner<T>>,
+}
+
+impl<T> Shared<T> {
+    fn new(value: T) -> Self {
+        let inner = Box::new(SharedInner {
+            value: UnsafeCell::new(Some(value)),
+            borrow: Cell::new(UNUSED),
+            strong: Cell::new(1),
+        });
+        Self {
+            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) },
+            marker: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &SharedInner<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Panics if the value is currently mutably borrowed, or already taken.
+    fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics if the value is currently borrowed, or already taken.
+    fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.try_borrow_mut().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_borrow(&self) -> Result<Ref<'_, T>, AccessError> {
+        let inner = self.inner();
+        let b = inner.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        let value = unsafe { &*inner.value.get() }
+            .as_ref()
+            .ok_or(AccessError::Taken)?;
+        inner.borrow.set(b + 1);
+        Ok(Ref {
+            ptr: value as *const T,
+            borrow: &inner.borrow,
+        })
+    }
+
+    fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, AccessError> {
+        let inner = self.inner();
+        let b = inner.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        if b > UNUSED {
+            return Err(AccessError::AlreadyBorrowed);
+        }
+        let value = unsafe { &mut *inner.value.get() }
+            .as_mut()
+            .ok_or(AccessError::Taken)?;
+        inner.borrow.set(-1);
+        Ok(RefMut {
+            ptr: value as *mut T,
+            borrow: &inner.borrow,
+        })
+    }
+
+    /// Moves the inner value out, succeeding only when no guard is
+    /// currently outstanding. Leaves the (still-shared) allocation in a
+    /// tombstone state: any later borrow from this or a cloned `Shared`
+    /// returns `Err`.
+    fn take(self) -> Result<T, AccessError> {
+        let inner = self.inner();
+        let b = inner.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        if b > UNUSED {
+            return Err(AccessError::AlreadyBorrowed);
+        }
+        unsafe { &mut *inner.value.get() }.take().ok_or(AccessError::Taken)
+    }
+
+    /// Panics if the value is currently mutably borrowed, or already taken.
+    fn borrow_owned(&self) -> OwnedRef<T> {
+        self.try_borrow_owned().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics if the value is currently borrowed, or already taken.
+    fn borrow_mut_owned(&self) -> OwnedRefMut<T> {
+        self.try_borrow_mut_owned()
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `try_borrow`, but the returned guard owns a strong reference
+    /// to the allocation instead of borrowing `self`, so it can outlive
+    /// the scope it was created in — including across an `.await` point.
+    fn try_borrow_owned(&self) -> Result<OwnedRef<T>, AccessError> {
+        let inner = self.inner();
+        let b = inner.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        if unsafe { &*inner.value.get() }.is_none() {
+            return Err(AccessError::Taken);
+        }
+        inner.borrow.set(b + 1);
+        Ok(OwnedRef {
+            shared: self.clone(),
+        })
+    }
+
+    /// Like `try_borrow_mut`, but the returned guard owns a strong
+    /// reference to the allocation instead of borrowing `self`, so it can
+    /// outlive the scope it was created in — including across an `.await`
+    /// point.
+    fn try_borrow_mut_owned(&self) -> Result<OwnedRefMut<T>, AccessError> {
+        let inner = self.inner();
+        let b = inner.borrow.get();
+        if b < UNUSED {
+            return Err(AccessError::AlreadyMutablyBorrowed);
+        }
+        if b > UNUSED {
+            return Err(AccessError::AlreadyBorrowed);
+        }
+        if unsafe { &*inner.value.get() }.is_none() {
+            return Err(AccessError::Taken);
+        }
+        inner.borrow.set(-1);
+        Ok(OwnedRefMut {
+            shared: self.clone(),
+        })
+    }
+}
+
+/// An owned shared-borrow guard over a `Shared<T>`. Holding one keeps the
+/// backing allocation alive via its own strong count, so unlike `Ref` it
+/// has no borrowed lifetime and can be held across an `.await` point.
+struct OwnedRef<T> {
+    shared: Shared<T>,
+}
+
+impl<T> std::ops::Deref for OwnedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.shared.inner().value.get() }
+            .as_ref()
+            .expect("tombstoned while a guard was outstanding")
+    }
+}
+
+impl<T> Drop for OwnedRef<T> {
+    fn drop(&mut self) {
+        let inner = self.shared.inner();
+        inner.borrow.set(inner.borrow.get() - 1);
+    }
+}
+
+/// An owned mutable-borrow guard over a `Shared<T>`. Holding one keeps the
+/// backing allocation alive via its own strong count, so unlike `RefMut` it
+/// has no borrowed lifetime and can be held across an `.await` point.
+struct OwnedRefMut<T> {
+    shared: Shared<T>,
+}
+
+impl<T> std::ops::Deref for OwnedRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.shared.inner().value.get() }
+            .as_ref()
+            .expect("tombstoned while a guard was outstanding")
+    }
+}
+
+impl<T> std::ops::DerefMut for OwnedRefMut<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.shared.inner().value.get() }
+            .as_mut()
+            .expect("tombstoned while a guard was outstanding")
+    }
+}
+
+impl<T> Drop for OwnedRefMut<T> {
+    fn drop(&mut self) {
+        self.shared.inner().borrow.set(UNUSED);
+    }
+}
+
+/// Pairs an owned borrow guard with a future, forwarding `poll` to the
+/// inner future while keeping the guard (and so the borrow it represents)
+/// alive for as long as the wrapper is. Lets callers build single-threaded
+/// executors or generators that hold an `OwnedRef`/`OwnedRefMut` across
+/// `.await` points; the access flag is released when the guard's own
+/// `Drop` runs, once this wrapper is dropped.
+struct WithGuard<G, Fut> {
+    guard: G,
+    fut: Fut,
+}
+
+impl<G, Fut> WithGuard<G, Fut> {
+    fn new(guard: G, fut: Fut) -> Self {
+        Self { guard, fut }
+    }
+}
+
+impl<G, Fut: Future> Future for WithGuard<G, Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is never moved out of `self`; this is the standard
+        // structural-pin-projection pattern for a field we own outright.
+        unsafe { self.map_unchecked_mut(|s| &mut s.fut) }.poll(cx)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        let strong = self.inner().strong.get();
+        self.inner().strong.set(strong + 1);
+        Self {
+            ptr: self.ptr,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let strong = self.inner().strong.get();
+        self.inner().strong.set(strong - 1);
+        if strong == 1 {
+            unsafe { drop(Box::from_raw(self.ptr.as_ptr())) };
+        }
+    }
+}
+
+/// A `Sync` sibling of `UnsafeShared` for cross-thread interior mutability
+/// that avoids a full `Mutex`: borrows are arbitrated with a lock-free,
+/// optimistic compare-and-swap on an `AtomicIsize`, which is cheap when
+/// contention between borrows is rare.
+struct SyncShared<T> {
+    inner: UnsafeCell<T>,
+    borrow: AtomicIsize,
+}
+
+unsafe impl<T: Send + Sync> Sync for SyncShared<T> {}
+
+/// A checked shared-borrow guard over a `SyncShared`. Releases its borrow
+/// on drop.
+struct SyncRef<'a, T: 'a> {
+    ptr: *const T,
+    borrow: &'a AtomicIsize,
+}
+
+impl<'a, T> std::ops::Deref for SyncRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T> Drop for SyncRef<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// A checked mutable-borrow guard over a `SyncShared`. Releases its borrow
+/// on drop.
+struct SyncRefMut<'a, T: 'a> {
+    ptr: *mut T,
+    borrow: &'a AtomicIsize,
+}
+
+impl<'a, T> std::ops::Deref for SyncRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T> std::ops::DerefMut for SyncRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'a, T> Drop for SyncRefMut<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.store(UNUSED, Ordering::Release);
+    }
+}
+
+impl<T> SyncShared<T> {
+    fn new(value: T) -> Self {
+        Self {
+            inner: UnsafeCell::new(value),
+            borrow: AtomicIsize::new(UNUSED),
+        }
+    }
+
+    /// Panics if the value is currently mutably borrowed.
+    fn borrow(&self) -> SyncRef<'_, T> {
+        self.try_borrow().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics if the value is currently borrowed, mutably or otherwise.
+    fn borrow_mut(&self) -> SyncRefMut<'_, T> {
+        self.try_borrow_mut().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Optimistically spins a compare-and-swap loop, bumping the shared
+    /// borrow count. Aborts as soon as it observes a writer.
+    fn try_borrow(&self) -> Result<SyncRef<'_, T>, AccessError> {
+        loop {
+            let cur = self.borrow.load(Ordering::Acquire);
+            if cur < 0 {
+                return Err(AccessError::AlreadyMutablyBorrowed);
+            }
+            match self.borrow.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Ok(SyncRef {
+                        ptr: self.inner.get(),
+                        borrow: &self.borrow,
+                    })
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// A single compare-and-swap from the unborrowed state to a writer
+    /// sentinel; fails immediately on any contention.
+    fn try_borrow_mut(&self) -> Result<SyncRefMut<'_, T>, AccessError> {
+        match self.borrow.compare_exchange(
+            UNUSED,
+            isize::MIN,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => Ok(SyncRefMut {
+                ptr: self.inner.get(),
+                borrow: &self.borrow,
+            }),
+            Err(actual) if actual < UNUSED => Err(AccessError::AlreadyMutablyBorrowed),
+            Err(_) => Err(AccessError::AlreadyBorrowed),
+        }
+    }
+
+    /// `&mut self` already proves there are no outstanding `SyncRef`/
+    /// `SyncRefMut` guards, so this needs no CAS loop and no `unsafe` at
+    /// the call site.
+    fn get_mut(&mut self) -> &mut T {
+        debug_assert_eq!(
+            *self.borrow.get_mut(),
+            UNUSED,
+            "borrow flag should be clear given exclusive access"
+        );
+        *self.borrow.get_mut() = UNUSED;
+        self.inner.get_mut()
+    }
+
+    fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T> From<T> for SyncShared<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
 }
 
 fn main() {
     let flag = UnsafeShared::new(NonCopyBool(false));
 
-    let c1 = || *flag.as_ref() = NonCopyBool(true);
-    let c2 = || println!("{:?}", *flag.as_ref());
+    let c1 = || *flag.borrow_mut() = NonCopyBool(true);
+    let c2 = || println!("{:?}", *flag.borrow());
 
     c1();
     c2();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: Future>(mut fut: F) -> F::Output {
+        fn no_op(_: *const ()) {}
+        fn clone(_: *const ()) -> std::task::RawWaker {
+            raw_waker()
+        }
+        fn raw_waker() -> std::task::RawWaker {
+            static VTABLE: std::task::RawWakerVTable =
+                std::task::RawWakerVTable::new(clone, no_op, no_op, no_op);
+            std::task::RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { std::task::Waker::from_raw(raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = unsafe { Pin::new_unchecked(&mut fut) };
+        loop {
+            if let Poll::Ready(value) = fut.as_mut().poll(&mut cx) {
+                return value;
+            }
+        }
+    }
+
+    /// Recovers the formatted panic message from a `catch_unwind` payload,
+    /// so tests can assert on *which* `AccessError` fired instead of just
+    /// that some panic happened.
+    fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn unsafe_shared_borrow_mut_conflicts_with_borrow() {
+        let shared = UnsafeShared::new(NonCopyBool(false));
+        let guard = shared.borrow();
+        assert!(!guard.0);
+        assert!(shared.try_borrow_mut().is_err());
+        drop(guard);
+        assert!(shared.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn unsafe_shared_borrow_mut_reentrant_panics_with_distinct_message() {
+        let shared = UnsafeShared::new(1i32);
+        let _guard = shared.borrow_mut();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = shared.borrow_mut();
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(err), "already mutably borrowed");
+    }
+
+    #[test]
+    fn sync_shared_borrow_mut_reentrant_panics_with_distinct_message() {
+        let shared = SyncShared::new(1i32);
+        let _guard = shared.borrow_mut();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = shared.borrow_mut();
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(err), "already mutably borrowed");
+    }
+
+    #[test]
+    fn unsafe_shared_borrow_conflicts_with_borrow_mut() {
+        let shared = UnsafeShared::new(1i32);
+        let mut guard = shared.borrow_mut();
+        *guard = 2;
+        assert!(shared.try_borrow().is_err());
+        drop(guard);
+        assert_eq!(*shared.borrow(), 2);
+    }
+
+    #[test]
+    fn unsafe_shared_as_unsafe_ref_bypasses_borrow_state() {
+        let shared = UnsafeShared::new(1i32);
+        unsafe {
+            let mut raw = shared.as_unsafe_ref();
+            *raw = 42;
+        }
+        assert_eq!(*shared.borrow(), 42);
+    }
+
+    #[test]
+    fn shared_clone_shares_the_allocation() {
+        let a = Shared::new(1i32);
+        let b = a.clone();
+        *a.borrow_mut() = 2;
+        assert_eq!(*b.borrow(), 2);
+    }
+
+    #[test]
+    fn shared_take_fails_while_borrowed_then_tombstones() {
+        let a = Shared::new(1i32);
+        let b = a.clone();
+        let guard = b.borrow();
+        assert!(a.clone().take().is_err());
+        drop(guard);
+        assert_eq!(a.clone().take().unwrap(), 1);
+        assert!(b.try_borrow().is_err());
+    }
+
+    #[test]
+    fn shared_borrow_after_take_panics_with_taken_message_not_borrow_conflict() {
+        let a = Shared::new(1i32);
+        a.clone().take().unwrap();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = a.borrow();
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(err), "value was already taken");
+    }
+
+    #[test]
+    fn unsafe_shared_cell_api_roundtrips() {
+        let shared = UnsafeShared::new(1i32);
+        assert_eq!(shared.get(), 1);
+        shared.set(2);
+        assert_eq!(shared.replace(3), 2);
+        shared.update(|v| v + 1);
+        assert_eq!(shared.get(), 4);
+
+        let other = UnsafeShared::new(10i32);
+        shared.swap(&other);
+        assert_eq!(shared.get(), 10);
+        assert_eq!(other.get(), 4);
+
+        let default_taker = UnsafeShared::new(7i32);
+        assert_eq!(default_taker.take(), 7);
+        assert_eq!(default_taker.get(), 0);
+    }
+
+    #[test]
+    fn unsafe_shared_cell_api_panics_while_borrowed() {
+        let shared = UnsafeShared::new(1i32);
+        let _guard = shared.borrow();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| shared.get()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sync_shared_borrow_then_borrow_mut_conflicts() {
+        let shared = SyncShared::new(5i32);
+        let guard = shared.borrow();
+        assert_eq!(*guard, 5);
+        assert!(shared.try_borrow_mut().is_err());
+        drop(guard);
+        assert!(shared.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn sync_shared_try_borrow_rejects_concurrent_writer() {
+        let shared = std::sync::Arc::new(SyncShared::new(0i32));
+        let writer = shared.borrow_mut();
+
+        let reader_shared = shared.clone();
+        let saw_conflict = std::thread::spawn(move || reader_shared.try_borrow().is_err())
+            .join()
+            .unwrap();
+        assert!(saw_conflict);
+
+        drop(writer);
+        assert!(shared.try_borrow().is_ok());
+    }
+
+    #[test]
+    fn sync_shared_only_one_of_two_concurrent_writers_wins() {
+        let shared = std::sync::Arc::new(SyncShared::new(0i32));
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let shared = shared.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    match shared.try_borrow_mut() {
+                        Ok(guard) => {
+                            std::thread::sleep(std::time::Duration::from_millis(50));
+                            drop(guard);
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                })
+            })
+            .collect();
+
+        let wins: usize = handles
+            .into_iter()
+            .map(|h| h.join().unwrap() as usize)
+            .sum();
+        assert_eq!(wins, 1);
+    }
+
+    #[test]
+    fn owned_ref_keeps_value_alive_after_original_shared_is_dropped() {
+        let a = Shared::new(1i32);
+        let owned = a.borrow_owned();
+        drop(a);
+        assert_eq!(*owned, 1);
+    }
+
+    #[test]
+    fn owned_ref_blocks_take_until_dropped() {
+        let a = Shared::new(1i32);
+        let owned = a.clone().borrow_owned();
+        assert!(a.clone().take().is_err());
+        drop(owned);
+        assert_eq!(a.take().unwrap(), 1);
+    }
+
+    #[test]
+    fn owned_ref_mut_writes_through_and_releases_on_drop() {
+        let a = Shared::new(1i32);
+        let mut owned = a.borrow_mut_owned();
+        *owned = 2;
+        assert!(a.try_borrow().is_err());
+        drop(owned);
+        assert_eq!(*a.borrow(), 2);
+    }
+
+    #[test]
+    fn borrow_mut_owned_reentrant_panics_with_distinct_message() {
+        let a = Shared::new(1i32);
+        let _owned = a.borrow_mut_owned();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = a.borrow_mut_owned();
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(err), "already mutably borrowed");
+    }
+
+    #[test]
+    fn borrow_owned_after_take_panics_with_taken_message() {
+        let a = Shared::new(1i32);
+        a.clone().take().unwrap();
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = a.borrow_owned();
+        }))
+        .unwrap_err();
+        assert_eq!(panic_message(err), "value was already taken");
+    }
+
+    #[test]
+    fn with_guard_forwards_poll_and_releases_guard_on_drop() {
+        let shared = Shared::new(1i32);
+        let with_guard = WithGuard::new(shared.borrow_owned(), std::future::ready(5));
+        assert_eq!(*with_guard.guard, 1);
+
+        let result = block_on(with_guard);
+        assert_eq!(result, 5);
+
+        assert!(shared.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn unsafe_shared_get_mut_into_inner_and_from() {
+        let mut shared = UnsafeShared::from(1i32);
+        *shared.get_mut() = 2;
+        assert_eq!(shared.into_inner(), 2);
+    }
+
+    #[test]
+    fn sync_shared_get_mut_into_inner_and_from() {
+        let mut shared = SyncShared::from(1i32);
+        *shared.get_mut() = 2;
+        assert_eq!(shared.into_inner(), 2);
+    }
+}